@@ -1,23 +1,224 @@
+use bzip2::write::BzEncoder;
 use chrono::Local;
 use env_logger::{self, Builder};
+use flate2::read::MultiGzDecoder;
 use flate2::{read, write, Compression};
 use getopts::Options;
+use gzp::deflate::Bgzf;
+use gzp::par::compress::ParCompressBuilder;
 use log::{info, LevelFilter};
+use lz4::EncoderBuilder as Lz4EncoderBuilder;
+use rayon::ThreadPoolBuilder;
+use snap::raw::Encoder as SnappyEncoder;
+use std::collections::BTreeMap;
 use std::env;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader, Read, Write};
 use std::path::Path;
 use std::process::exit;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
 
 static VERSION: &str = "0.1.1";
 
+// Detected or user-specified input encoding. Needed up front because stdin
+// can't be seeked to sniff magic bytes, so a stream requires an explicit
+// `--informat`.
+#[derive(Clone, Copy)]
+enum InFormat {
+    Fasta,
+    FastaGz,
+    FastaXz,
+}
+
+fn parse_informat(s: &str) -> InFormat {
+    match s {
+        "fasta" => InFormat::Fasta,
+        "fasta.gz" => InFormat::FastaGz,
+        "fasta.xz" => InFormat::FastaXz,
+        _ => panic!(
+            "Unknown --informat '{}'! Expected one of: fasta, fasta.gz, fasta.xz",
+            s
+        ),
+    }
+}
+
+fn detect_informat(path: &str) -> InFormat {
+    let lower = path.to_lowercase();
+    if lower.ends_with(".fa.gz") || lower.ends_with(".fasta.gz") {
+        InFormat::FastaGz
+    } else if lower.ends_with(".fa.xz") || lower.ends_with(".fasta.xz") {
+        InFormat::FastaXz
+    } else if lower.ends_with(".fa") || lower.ends_with(".fasta") {
+        InFormat::Fasta
+    } else {
+        panic!(
+            "{} does not seem to be a FASTA file (expected .fa[.gz|.xz] or .fasta[.gz|.xz])!",
+            path
+        )
+    }
+}
+
+// Which strand(s) to emit a BED row for.
+#[derive(Clone, Copy)]
+enum Strand {
+    Plus,
+    Minus,
+    Both,
+}
+
+impl Strand {
+    // How many BED rows one interval produces under this setting.
+    fn rows_per_interval(self) -> u64 {
+        match self {
+            Strand::Both => 2,
+            _ => 1,
+        }
+    }
+}
+
+// How the output BED is wrapped. Plain writes unwrapped text; Gzip wraps
+// every line in its own standalone gzip member (the legacy `--outFile *.gz`
+// behavior); Bgzf writes the standard BGZF block-gzip format so the result
+// can be `tabix`-indexed.
+#[derive(Clone, Copy, PartialEq)]
+enum OutMode {
+    Plain,
+    Gzip,
+    Bgzf,
+}
+
+fn detect_outmode(path: &str) -> OutMode {
+    let lower = path.to_lowercase();
+    if lower.ends_with(".bgz") || lower.ends_with(".bgzf") {
+        OutMode::Bgzf
+    } else if lower.ends_with(".gz") {
+        OutMode::Gzip
+    } else {
+        OutMode::Plain
+    }
+}
+
+fn parse_strand(s: &str) -> Strand {
+    match s {
+        "+" => Strand::Plus,
+        "-" => Strand::Minus,
+        "both" => Strand::Both,
+        _ => panic!("Unknown --strand '{}'! Expected one of: +, -, both", s),
+    }
+}
+
+// Reverse-complements an uppercased FASTA sequence; any base outside ACGTN
+// (e.g. IUPAC ambiguity codes) is passed through unchanged.
+fn reverse_complement(seq: &str) -> String {
+    seq.chars()
+        .rev()
+        .map(|c| match c {
+            'A' => 'T',
+            'T' => 'A',
+            'C' => 'G',
+            'G' => 'C',
+            'N' => 'N',
+            other => other,
+        })
+        .collect()
+}
+
+// One compressed-length measurement per interval. Implementations only need
+// to report how many bytes the payload shrinks to; the window/model tradeoffs
+// of each algorithm are left to the underlying crate.
+trait Compressor {
+    fn compressed_len(&self, data: &[u8]) -> usize;
+}
+
+struct DeflateCompressor;
+impl Compressor for DeflateCompressor {
+    fn compressed_len(&self, data: &[u8]) -> usize {
+        let mut e = write::DeflateEncoder::new(Vec::new(), Compression::best());
+        e.write_all(data).expect("Compression failed!");
+        e.finish().expect("Compression failed!").len()
+    }
+}
+
+struct XzCompressor;
+impl Compressor for XzCompressor {
+    fn compressed_len(&self, data: &[u8]) -> usize {
+        let mut e = XzEncoder::new(Vec::new(), 9);
+        e.write_all(data).expect("Compression failed!");
+        e.finish().expect("Compression failed!").len()
+    }
+}
+
+struct ZstdCompressor;
+impl Compressor for ZstdCompressor {
+    fn compressed_len(&self, data: &[u8]) -> usize {
+        zstd::encode_all(data, 19)
+            .expect("Compression failed!")
+            .len()
+    }
+}
+
+struct Bzip2Compressor;
+impl Compressor for Bzip2Compressor {
+    fn compressed_len(&self, data: &[u8]) -> usize {
+        let mut e = BzEncoder::new(Vec::new(), bzip2::Compression::best());
+        e.write_all(data).expect("Compression failed!");
+        e.finish().expect("Compression failed!").len()
+    }
+}
+
+struct Lz4Compressor;
+impl Compressor for Lz4Compressor {
+    fn compressed_len(&self, data: &[u8]) -> usize {
+        let mut e = Lz4EncoderBuilder::new()
+            .build(Vec::new())
+            .expect("Compression failed!");
+        e.write_all(data).expect("Compression failed!");
+        let (buf, result) = e.finish();
+        result.expect("Compression failed!");
+        buf.len()
+    }
+}
+
+struct SnappyCompressor;
+impl Compressor for SnappyCompressor {
+    fn compressed_len(&self, data: &[u8]) -> usize {
+        SnappyEncoder::new()
+            .compress_vec(data)
+            .expect("Compression failed!")
+            .len()
+    }
+}
+
+fn resolve_compressor(name: &str) -> Box<dyn Compressor + Send + Sync> {
+    match name {
+        "deflate" => Box::new(DeflateCompressor),
+        "xz" => Box::new(XzCompressor),
+        "zstd" => Box::new(ZstdCompressor),
+        "bzip2" => Box::new(Bzip2Compressor),
+        "lz4" => Box::new(Lz4Compressor),
+        "snappy" => Box::new(SnappyCompressor),
+        _ => panic!(
+            "Unknown --compressor '{}'! Expected one of: deflate, xz, zstd, bzip2, lz4, snappy",
+            name
+        ),
+    }
+}
+
 struct Params {
     infile: String,
     outfile: Option<String>,
     seqlen: u32,
-    infh: BufReader<File>,
-    outfh: Box<dyn Write>,
-    gzout: bool,
+    infh: BufReader<Box<dyn Read + Send>>,
+    outfh: Box<dyn Write + Send>,
+    out_mode: OutMode,
+    legacy_gzip_len: bool,
+    compressor_name: String,
+    compressor: Box<dyn Compressor + Send + Sync>,
+    threads: usize,
+    strand: Strand,
 }
 
 fn init_logger() {
@@ -39,10 +240,10 @@ fn usage(arg0: &str, opts: Options) {
     let s = format!(
         "\
 Summary:
-    Computes GZIP compressibility for genomic regions in every given interval (--seqlen)
+    Computes compressibility (DEFLATE by default, see --compressor) for genomic regions in every given interval (--seqlen)
 
 Usage:
-    {} --inFile hg38.fa --outfile output.bed [--seqlen 50] [--version|-v] [--help|-h]
+    {} --inFile hg38.fa --outFile output.bed --seqlen 50 [--compressor deflate] [--threads N] [--strand +] [--legacy-gzip-len] [--informat fasta] [--version|-v] [--help|-h]
 
 Output:
     The output has 6 columns:
@@ -50,7 +251,7 @@ Output:
         2) start coordinate;
         3) end coordinate;
         4) sequence;
-        5) GZIP compressibility ();
+        5) compressibility (computed by --compressor);
         6) genome strand;",
         arg0
     );
@@ -58,9 +259,43 @@ Output:
 }
 
 fn proc_args(args: &Vec<String>, mut opts: Options) -> Params {
-    opts.optopt("i", "inFile", "", "input file in FASTA format");
-    opts.optopt("o", "outFile", "", "output file; if omitted, write to STDOUT; otherwise, if ending with '.gz', will be GZ compressed");
+    opts.optopt(
+        "i",
+        "inFile",
+        "",
+        "input file in FASTA format, optionally .gz/.xz compressed; '-' or omitted reads FASTA from STDIN",
+    );
+    opts.optopt(
+        "",
+        "informat",
+        "",
+        "input format: fasta, fasta.gz, fasta.xz; required when reading from STDIN since it can't be sniffed",
+    );
+    opts.optopt("o", "outFile", "", "output file; if omitted, write to STDOUT; otherwise, if ending with '.gz', will be GZ compressed, or '.bgz'/'.bgzf', will be BGZF block-compressed for tabix indexing");
     opts.optopt("l", "seqlen", "", "length (bp) of the intervals");
+    opts.optopt(
+        "c",
+        "compressor",
+        "",
+        "compressor used to estimate compressibility: deflate (default), xz, zstd, bzip2, lz4, snappy",
+    );
+    opts.optopt(
+        "p",
+        "threads",
+        "",
+        "number of worker threads to compress intervals with (default: number of CPUs)",
+    );
+    opts.optopt(
+        "",
+        "strand",
+        "",
+        "strand(s) to emit: + (default), -, or both (reverse-complements the window for -)",
+    );
+    opts.optflag(
+        "",
+        "legacy-gzip-len",
+        "measure compressibility with best-level GZIP and subtract the 10-byte header, matching v0.1.1 output instead of raw DEFLATE",
+    );
     opts.optflag("h", "help", "print usage");
     opts.optflag("v", "version", "print version");
     let matches = opts.parse(&args[1..]).unwrap();
@@ -73,43 +308,42 @@ fn proc_args(args: &Vec<String>, mut opts: Options) -> Params {
         exit(0);
     }
     let infile = match matches.opt_str("inFile") {
-        Some(f) => match Path::new(&f).exists() {
-            true => match &*(f
-                .split('.')
-                .last()
-                .expect("Faied to find the file extension!")
-                .to_lowercase())
-            {
-                "fa" => f,
-                _ => panic!("{} does not seem to be a FASTA file!", f),
-            },
-            false => panic!("{} does not exist!", f),
-        },
-        None => panic!("--inFile is empty!"),
+        Some(f) if f != "-" => f,
+        _ => "-".to_string(),
+    };
+    let informat = matches.opt_str("informat").map(|s| parse_informat(&s));
+
+    let (raw, informat): (Box<dyn Read + Send>, InFormat) = if infile == "-" {
+        let informat = informat
+            .expect("--informat is required when reading FASTA from STDIN (can't sniff a stream)!");
+        (Box::new(io::stdin()), informat)
+    } else {
+        if !Path::new(&infile).exists() {
+            panic!("{} does not exist!", infile);
+        }
+        let informat = informat.unwrap_or_else(|| detect_informat(&infile));
+        let f = File::open(infile.as_str())
+            .expect(&format!("Failed to open {} for read!", infile.as_str()));
+        (Box::new(f), informat)
+    };
+    let infh: Box<dyn Read + Send> = match informat {
+        InFormat::Fasta => raw,
+        InFormat::FastaGz => Box::new(MultiGzDecoder::new(raw)),
+        InFormat::FastaXz => Box::new(XzDecoder::new(raw)),
     };
-    let infh = File::open(infile.as_str())
-        .expect(&format!("Failed to open {} for read!", infile.as_str()));
     let infh = BufReader::new(infh);
 
     let outfile = matches.opt_str("outFile");
 
     let outfh = match &outfile {
         Some(s) => Box::new(File::create(s).expect(&format!("Cannot open {} for write!", s)))
-            as Box<dyn Write>,
-        None => Box::new(io::stdout()) as Box<dyn Write>,
+            as Box<dyn Write + Send>,
+        None => Box::new(io::stdout()) as Box<dyn Write + Send>,
     };
 
-    let gzout = match &outfile {
-        Some(s) => match &*(s
-            .split('.')
-            .last()
-            .expect("Unknown file extension!")
-            .to_lowercase())
-        {
-            "gz" => true,
-            _ => false,
-        },
-        None => false,
+    let out_mode = match &outfile {
+        Some(s) => detect_outmode(s),
+        None => OutMode::Plain,
     };
 
     let seqlen = match matches.opt_str("seqlen") {
@@ -120,146 +354,395 @@ fn proc_args(args: &Vec<String>, mut opts: Options) -> Params {
         _ => panic!("--seqlen incorrect!"),
     };
 
+    let legacy_gzip_len = matches.opt_present("legacy-gzip-len");
+
+    let compressor_name = matches
+        .opt_str("compressor")
+        .unwrap_or_else(|| "deflate".to_string())
+        .to_lowercase();
+    let compressor = resolve_compressor(&compressor_name);
+
+    if legacy_gzip_len && compressor_name != "deflate" {
+        panic!(
+            "--legacy-gzip-len is only meaningful alongside the default 'deflate' compressor, not '{}'!",
+            compressor_name
+        );
+    }
+
+    // 0 means "let rayon pick", which defaults to one worker per CPU.
+    let threads = match matches.opt_str("threads") {
+        Some(s) => s.parse::<usize>().expect("--threads cannot be parsed!"),
+        None => 0,
+    };
+
+    let strand = matches
+        .opt_str("strand")
+        .map(|s| parse_strand(&s))
+        .unwrap_or(Strand::Plus);
+
     let params = Params {
         infile: infile,
         outfile: outfile,
         seqlen: seqlen,
         infh: infh,
         outfh: outfh,
-        gzout: gzout,
+        out_mode: out_mode,
+        legacy_gzip_len: legacy_gzip_len,
+        compressor_name: compressor_name,
+        compressor: compressor,
+        threads: threads,
+        strand: strand,
     };
     return params;
 }
 
+// Returns the compressed length of `seq` in bytes, via `compressor` by
+// default. `--legacy-gzip-len` overrides this with the v0.1.1 behavior of
+// gzip-compressing and subtracting a fixed 10-byte header, for callers who
+// need bit-for-bit compatible BED output; it is only meaningful alongside the
+// default `deflate` compressor. As in v0.1.1, the legacy gzip output is read
+// once into a buffer of `window` (the --seqlen interval length) bytes.
+fn compressed_len(
+    seq: &[u8],
+    window: usize,
+    legacy_gzip_len: bool,
+    compressor: &dyn Compressor,
+) -> usize {
+    if legacy_gzip_len {
+        let mut buf = vec![0u8; window];
+        let mut gz = read::GzEncoder::new(seq, Compression::best());
+        match gz.read(&mut buf) {
+            Ok(x) => x - 10,
+            Err(e) => panic!("Compression failed! {}", e),
+        }
+    } else {
+        compressor.compressed_len(seq)
+    }
+}
+
+fn format_bed_line(chr: &str, start: u32, end: u32, seq: &str, ratio: f32, strand: &str) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\n",
+        chr, start, end, seq, ratio, strand
+    )
+}
+
+// Settings shared by every job in a run: how to measure compressibility and
+// how to format the result. Bundled behind one `Arc` so `spawn_job` doesn't
+// need a parameter per setting.
+struct JobConfig {
+    legacy_gzip_len: bool,
+    compressor: Box<dyn Compressor + Send + Sync>,
+    strand: Strand,
+    gzout: bool,
+}
+
+// One unit of dispatchable work: a single genomic interval, tagged with the
+// row index its BED line(s) should be flushed at.
+struct Interval {
+    idx: u64,
+    chr: String,
+    start: u32,
+    end: u32,
+    seq: String,
+}
+
+// Compresses one interval for the requested strand(s) and sends its BED
+// line(s), tagged starting at `interval.idx`, to the writer thread.
+// `--strand both` emits two rows (`+` then `-`) at `idx` and `idx + 1`. Jobs
+// are dispatched in genomic order but may finish out of order, so the writer
+// re-sorts by tag before flushing.
+fn spawn_job<'scope>(
+    scope: &rayon::Scope<'scope>,
+    interval: Interval,
+    config: Arc<JobConfig>,
+    tx: mpsc::SyncSender<(u64, Vec<u8>)>,
+) {
+    scope.spawn(move |_| {
+        let Interval {
+            idx,
+            chr,
+            start,
+            end,
+            seq,
+        } = interval;
+        let emit = |tag: u64, seq: &str, strand_label: &str| {
+            let clen = compressed_len(
+                seq.as_bytes(),
+                (end - start) as usize,
+                config.legacy_gzip_len,
+                config.compressor.as_ref(),
+            );
+            let r = seq.as_bytes().len() as f32 / clen as f32;
+            let line = format_bed_line(&chr, start, end, seq, r, strand_label);
+            let bytes = if config.gzout {
+                let mut e = write::GzEncoder::new(Vec::new(), Compression::default());
+                e.write_all(line.as_bytes()).unwrap();
+                e.finish().unwrap()
+            } else {
+                line.into_bytes()
+            };
+            tx.send((tag, bytes)).expect("Writer thread is gone!");
+        };
+        match config.strand {
+            Strand::Plus => emit(idx, &seq, "+"),
+            Strand::Minus => emit(idx, &reverse_complement(&seq), "-"),
+            Strand::Both => {
+                emit(idx, &seq, "+");
+                emit(idx + 1, &reverse_complement(&seq), "-");
+            }
+        }
+    });
+}
+
+// The chromosome name is the BED line's first tab-delimited column.
+fn bed_chrom(line: &[u8]) -> &[u8] {
+    match line.iter().position(|&b| b == b'\t') {
+        Some(p) => &line[..p],
+        None => line,
+    }
+}
+
+// Drains formatted BED lines (gzip-wrapped in `.gz` mode) off `rx` and writes them to `outfh` in genomic
+// order, buffering any that arrive ahead of their turn. When `bgzf` is set,
+// flushes on every chromosome change so each chromosome starts on a fresh
+// BGZF block boundary, which is what lets `tabix` seek directly to it.
+fn write_ordered_results(
+    mut outfh: Box<dyn Write + Send>,
+    rx: mpsc::Receiver<(u64, Vec<u8>)>,
+    bgzf: bool,
+) {
+    let mut pending: BTreeMap<u64, Vec<u8>> = BTreeMap::new();
+    let mut next = 0u64;
+    let mut current_chrom: Option<Vec<u8>> = None;
+    for (idx, bytes) in rx {
+        pending.insert(idx, bytes);
+        while let Some(bytes) = pending.remove(&next) {
+            if bgzf {
+                let chrom = bed_chrom(&bytes);
+                if current_chrom.as_deref() != Some(chrom) {
+                    outfh.flush().expect("Failed to flush BGZF block boundary!");
+                    current_chrom = Some(chrom.to_vec());
+                }
+            }
+            outfh.write_all(&bytes).expect("Failed to write output!");
+            next += 1;
+        }
+    }
+    outfh.flush().expect("Failed to flush output!");
+}
+
 fn main() {
     init_logger();
     let args: Vec<String> = env::args().collect();
     let params = proc_args(&args, Options::new());
-    proc_args(&args, Options::new());
     info!(
-        "{{ infile = {}, outfile = {}, seqlen = {}, gzout = {}, VERSION = {} }}",
+        "{{ infile = {}, outfile = {}, seqlen = {}, out_mode = {}, compressor = {}, threads = {}, strand = {}, legacy_gzip_len = {}, VERSION = {} }}",
         &params.infile,
         match &params.outfile {
             Some(s) => s,
             None => "",
         },
         &params.seqlen,
-        match &params.gzout {
+        match &params.out_mode {
+            OutMode::Plain => "plain",
+            OutMode::Gzip => "gzip",
+            OutMode::Bgzf => "bgzf",
+        },
+        &params.compressor_name,
+        &params.threads,
+        match &params.strand {
+            Strand::Plus => "+",
+            Strand::Minus => "-",
+            Strand::Both => "both",
+        },
+        match &params.legacy_gzip_len {
             true => "true",
             false => "false",
         },
         VERSION
     );
     let infh = params.infh;
-    let mut outfh = params.outfh;
     let seqlen = params.seqlen;
-    let gzout = params.gzout;
+    let gzout = params.out_mode == OutMode::Gzip;
+    let bgzf = params.out_mode == OutMode::Bgzf;
+    let config = Arc::new(JobConfig {
+        legacy_gzip_len: params.legacy_gzip_len,
+        compressor: params.compressor,
+        strand: params.strand,
+        gzout: gzout,
+    });
+
+    let outfh: Box<dyn Write + Send> = if bgzf {
+        let mut builder = ParCompressBuilder::<Bgzf>::new();
+        if params.threads > 0 {
+            builder = builder
+                .num_threads(params.threads)
+                .expect("Invalid --threads for the BGZF writer!");
+        }
+        Box::new(builder.from_writer(params.outfh))
+    } else {
+        params.outfh
+    };
+
+    let mut pool_builder = ThreadPoolBuilder::new();
+    if params.threads > 0 {
+        pool_builder = pool_builder.num_threads(params.threads);
+    }
+    let pool = pool_builder
+        .build()
+        .expect("Failed to build the compression thread pool!");
+
+    let (result_tx, result_rx) =
+        mpsc::sync_channel::<(u64, Vec<u8>)>(pool.current_num_threads() * 4);
+
+    let writer = thread::spawn(move || write_ordered_results(outfh, result_rx, bgzf));
 
     let mut chr: String = String::new();
     let mut seq = String::new();
     let mut i: u32 = 0;
-    let mut _buf = vec![0u8; seqlen as usize];
+    let mut idx: u64 = 0;
     info!("Start processing FASTA...");
-    for line in infh.lines() {
-        let l = line.expect("Cannot read the current line!");
-        match &l[..1] {
-            ">" => {
-                if !seq.is_empty() {
-                    let s = &seq[..];
-                    let mut gz = read::GzEncoder::new(s.as_bytes(), Compression::best());
-                    let gzlen = match gz.read(&mut _buf) {
-                        Ok(x) => x,
-                        Err(e) => panic!(format!("Compression failed! {}", e)),
-                    };
-                    let r = seq.as_bytes().len() as f32 / (gzlen - 10) as f32;
-                    if !gzout {
-                        outfh
-                            .write(
-                                format!(
-                                    "{}\t{}\t{}\t{}\t{}\t{}\n",
-                                    chr,
-                                    i * seqlen,
-                                    (i + 1) * seqlen,
-                                    s,
-                                    r,
-                                    "+"
-                                )
-                                .as_bytes(),
-                            )
-                            .unwrap();
-                    } else {
-                        let mut e = write::GzEncoder::new(Vec::new(), Compression::default());
-                        e.write_all(
-                            format!(
-                                "{}\t{}\t{}\t{}\t{}\t{}\n",
-                                chr,
-                                i * seqlen,
-                                (i + 1) * seqlen,
-                                s,
-                                r,
-                                "+"
-                            )
-                            .as_bytes(),
-                        )
-                        .unwrap();
-                        outfh.write(&e.finish().unwrap()).unwrap();
+    pool.scope(|scope| {
+        for line in infh.lines() {
+            let l = line.expect("Cannot read the current line!");
+            match &l[..1] {
+                ">" => {
+                    if !seq.is_empty() {
+                        spawn_job(
+                            scope,
+                            Interval {
+                                idx,
+                                chr: chr.clone(),
+                                start: i * seqlen,
+                                end: (i + 1) * seqlen,
+                                seq: seq.clone(),
+                            },
+                            Arc::clone(&config),
+                            result_tx.clone(),
+                        );
+                        idx += config.strand.rows_per_interval();
+                        seq.clear();
                     }
-                    seq.clear();
+                    chr = l[1..].to_string();
+                    i = 0;
                 }
-                chr = l[1..].to_string();
-                i = 0;
-            }
-            _ => {
-                seq.push_str(&l.to_uppercase());
-                if seq.len() >= seqlen as usize {
-                    let s = &seq[..seqlen as usize];
-                    let mut gz = read::GzEncoder::new(s.as_bytes(), Compression::best());
-                    let gzlen = match gz.read(&mut _buf) {
-                        Ok(x) => x,
-                        Err(e) => panic!(format!("Compression failed! {}", e)),
-                    };
-                    // after compression GACTTGCAGTGGGGGGA becomes
-                    //          [1F,8B,08,00,00,00,00,00,02,FF,73,77,74,0E,09,71,77,76,74,0F,71,07,03,47,00]
-                    //           -----------header------------                                              -----footer(CRC32)-----
-                    // so we need to subtract 10-byte header
-                    let r = seq.as_bytes().len() as f32 / (gzlen - 10) as f32;
-                    if !gzout {
-                        outfh
-                            .write(
-                                format!(
-                                    "{}\t{}\t{}\t{}\t{}\t{}\n",
-                                    chr,
-                                    i * seqlen,
-                                    (i + 1) * seqlen,
-                                    s,
-                                    r,
-                                    "+"
-                                )
-                                .as_bytes(),
-                            )
-                            .unwrap(); // &_buf[0..gzlen] \n{:02X?}
-                    } else {
-                        let mut e = write::GzEncoder::new(Vec::new(), Compression::default());
-                        e.write_all(
-                            format!(
-                                "{}\t{}\t{}\t{}\t{}\t{}\n",
-                                chr,
-                                i * seqlen,
-                                (i + 1) * seqlen,
-                                s,
-                                r,
-                                "+"
-                            )
-                            .as_bytes(),
-                        )
-                        .unwrap();
-                        outfh.write(&e.finish().unwrap()).unwrap();
+                _ => {
+                    seq.push_str(&l.to_uppercase());
+                    if seq.len() >= seqlen as usize {
+                        let s = seq[..seqlen as usize].to_string();
+                        spawn_job(
+                            scope,
+                            Interval {
+                                idx,
+                                chr: chr.clone(),
+                                start: i * seqlen,
+                                end: (i + 1) * seqlen,
+                                seq: s,
+                            },
+                            Arc::clone(&config),
+                            result_tx.clone(),
+                        );
+                        idx += config.strand.rows_per_interval();
+                        seq = seq.chars().skip(seqlen as usize).collect();
+                        i += 1;
                     }
-                    seq = seq.chars().skip(seqlen as usize).collect();
-                    i += 1;
                 }
             }
-        };
-    }
+        }
+    });
+    drop(result_tx);
+    writer.join().expect("Writer thread panicked!");
     info!("All done!");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn reverse_complement_complements_and_reverses() {
+        assert_eq!(reverse_complement("ACGTN"), "NACGT");
+    }
+
+    #[test]
+    fn reverse_complement_passes_through_non_acgtn_codes() {
+        // IUPAC ambiguity codes (e.g. `R` = A or G) have no single complement
+        // base here, so they pass through unchanged rather than panicking.
+        assert_eq!(reverse_complement("ACGTR"), "RACGT");
+    }
+
+    // Lets `write_ordered_results` write into a buffer we can still read
+    // after the writer thread that owns it exits.
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_ordered_results_reorders_out_of_order_tags() {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let outfh: Box<dyn Write + Send> = Box::new(SharedBuf(Arc::clone(&buf)));
+        let (tx, rx) = mpsc::sync_channel(8);
+
+        // Send tags out of arrival order; the writer must still flush 0,1,2,3.
+        tx.send((2u64, b"two\n".to_vec())).unwrap();
+        tx.send((0u64, b"zero\n".to_vec())).unwrap();
+        tx.send((3u64, b"three\n".to_vec())).unwrap();
+        tx.send((1u64, b"one\n".to_vec())).unwrap();
+        drop(tx);
+
+        write_ordered_results(outfh, rx, false);
+
+        let written = buf.lock().unwrap().clone();
+        assert_eq!(written, b"zero\none\ntwo\nthree\n".to_vec());
+    }
+
+    #[test]
+    fn spawn_job_strand_both_tags_plus_and_minus_rows() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .expect("Failed to build test thread pool!");
+        let config = Arc::new(JobConfig {
+            legacy_gzip_len: false,
+            compressor: resolve_compressor("deflate"),
+            strand: Strand::Both,
+            gzout: false,
+        });
+        let (tx, rx) = mpsc::sync_channel(8);
+
+        pool.scope(|scope| {
+            spawn_job(
+                scope,
+                Interval {
+                    idx: 4,
+                    chr: "chr1".to_string(),
+                    start: 0,
+                    end: 4,
+                    seq: "ACGT".to_string(),
+                },
+                Arc::clone(&config),
+                tx,
+            );
+        });
+
+        let mut rows: Vec<(u64, Vec<u8>)> = rx.try_iter().collect();
+        rows.sort_by_key(|(tag, _)| *tag);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].0, 4);
+        assert_eq!(rows[1].0, 5);
+        let plus_line = String::from_utf8(rows[0].1.clone()).unwrap();
+        let minus_line = String::from_utf8(rows[1].1.clone()).unwrap();
+        assert!(plus_line.ends_with("+\n"));
+        assert!(minus_line.ends_with("-\n"));
+    }
+}